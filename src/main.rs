@@ -1,15 +1,39 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::future;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+use arrow::array::RecordBatch;
+use arrow::datatypes::{DataType, Field, Schema};
 use arrow::ipc::convert::try_schema_from_flatbuffer_bytes;
+use arrow::ipc::writer::IpcWriteOptions;
 use arrow_flight::decode::FlightRecordBatchStream;
-use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
 use arrow_flight::sql::server::{FlightSqlService, PeekableFlightDataStream};
-use arrow_flight::sql::{CommandStatementIngest, SqlInfo};
-use datafusion::datasource::file_format::parquet::ParquetSink;
-use datafusion::datasource::listing::ListingTableUrl;
+use arrow_flight::sql::action_end_transaction_request::EndTransaction;
+use arrow_flight::sql::command_statement_ingest::table_definition_options::TableExistsOption;
+use arrow_flight::sql::{
+    ActionBeginTransactionRequest, ActionBeginTransactionResult,
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult, ActionEndTransactionRequest, Any, CommandPreparedStatementQuery,
+    CommandStatementIngest, CommandStatementQuery, DoPutPreparedStatementResult, ProstMessageExt,
+    SqlInfo, TicketStatementQuery,
+};
+use arrow_flight::{
+    Action, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest,
+    HandshakeResponse, IpcMessage, PutResult, SchemaAsIpc, Ticket,
+};
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::file_format::parquet::{ParquetFormat, ParquetSink};
+use datafusion::datasource::listing::{ListingOptions, ListingTableUrl};
+use datafusion::datasource::MemTable;
 use datafusion::datasource::physical_plan::{FileSink, FileSinkConfig};
 use datafusion::error::DataFusionError;
+use datafusion::logical_expr::LogicalPlan;
 use datafusion::execution::object_store::{
     DefaultObjectStoreRegistry, ObjectStoreRegistry as _, ObjectStoreUrl,
 };
@@ -17,13 +41,20 @@ use datafusion::execution::runtime_env::RuntimeEnvBuilder;
 use datafusion::execution::{SendableRecordBatchStream, SessionState, SessionStateBuilder};
 use datafusion::logical_expr::dml::InsertOp;
 use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
-use datafusion::prelude::{SessionConfig, SessionContext};
+use datafusion::prelude::{ParquetReadOptions, SessionConfig, SessionContext};
+use datafusion::scalar::ScalarValue;
 use dotenvy::dotenv;
-use futures::{StreamExt as _, TryStreamExt as _};
+use futures::{Stream, StreamExt as _, TryStreamExt as _};
 use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::local::LocalFileSystem;
 use object_store::ObjectStore;
+use prost::Message as _;
+use tonic::metadata::MetadataMap;
 use tonic::transport::Server;
-use tonic::{Request, Status};
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
 
 #[cfg(feature = "dedicated-executor")]
 use crate::dedicated_executor::{DedicatedExecutor, DedicatedExecutorBuilder};
@@ -34,10 +65,703 @@ mod localstack;
 
 pub struct FlightSql {
     session: SessionState,
+    // Scheme the backing object store is registered under; table paths are
+    // resolved against it.
+    store_url: ObjectStoreUrl,
+    // Staged directory writes keyed by transaction id, published on commit.
+    transactions: Arc<Mutex<HashMap<String, Vec<StagedTable>>>>,
+    // Session tokens issued by a successful handshake.
+    tokens: Arc<Mutex<HashSet<String>>>,
+    // Prepared statement handle -> SQL text.
+    prepared: Arc<Mutex<HashMap<String, String>>>,
+    // Parameter values bound to a prepared statement handle, applied before
+    // execution.
+    bindings: Arc<Mutex<HashMap<String, Vec<ScalarValue>>>>,
+    next_id: Arc<AtomicU64>,
     #[cfg(feature = "dedicated-executor")]
     exec: DedicatedExecutor,
 }
 
+type DoGetStream = Pin<Box<dyn Stream<Item = Result<FlightData, Status>> + Send>>;
+
+// A directory write staged under a transaction, published into `target` on
+// commit. `replace` clears the target directory first (overwrite/merge writes);
+// an append leaves the existing file group in place.
+struct StagedTable {
+    staging: object_store::path::Path,
+    target: object_store::path::Path,
+    replace: bool,
+}
+
+fn df_to_status(e: DataFusionError) -> Status {
+    Status::internal(e.to_string())
+}
+
+// List every object under `prefix`.
+async fn list_prefix(
+    store: &Arc<dyn ObjectStore>,
+    prefix: &object_store::path::Path,
+) -> tonic::Result<Vec<object_store::path::Path>> {
+    store
+        .list(Some(prefix))
+        .map_ok(|meta| meta.location)
+        .try_collect()
+        .await
+        .map_err(|e| Status::internal(format!("list {prefix} failed: {e}")))
+}
+
+// Delete every object under `prefix`, clearing a published file group.
+async fn clear_prefix(
+    store: &Arc<dyn ObjectStore>,
+    prefix: &object_store::path::Path,
+) -> tonic::Result<()> {
+    for location in list_prefix(store, prefix).await? {
+        let _ = store.delete(&location).await;
+    }
+    Ok(())
+}
+
+// Merge semantics for an ingest, modeled on Seafowl's `DoPutCommand`. The mode
+// and its primary-key column set are read from the ingest ticket `options`
+// (`mode` = overwrite|upsert|delete, `primary_keys` = comma-separated columns).
+enum DoPutCommand {
+    Overwrite,
+    Upsert { keys: Vec<String> },
+    Delete { keys: Vec<String> },
+}
+
+impl DoPutCommand {
+    fn from_options(options: &HashMap<String, String>) -> tonic::Result<Self> {
+        let keys = || -> tonic::Result<Vec<String>> {
+            let raw = options.get("primary_keys").map(String::as_str).unwrap_or("");
+            let keys: Vec<String> = raw
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            if keys.is_empty() {
+                return Err(Status::invalid_argument(
+                    "upsert/delete require a non-empty `primary_keys` option",
+                ));
+            }
+            Ok(keys)
+        };
+
+        match options.get("mode").map(String::as_str) {
+            None | Some("overwrite") => Ok(Self::Overwrite),
+            Some("upsert") => Ok(Self::Upsert { keys: keys()? }),
+            Some("delete") => Ok(Self::Delete { keys: keys()? }),
+            Some(other) => Err(Status::invalid_argument(format!(
+                "unsupported ingest mode `{other}`"
+            ))),
+        }
+    }
+}
+
+impl FlightSql {
+    // Resolve an object path (relative to the store root) into a listing URL
+    // under the configured store scheme.
+    fn table_url(&self, rel: &str) -> tonic::Result<ListingTableUrl> {
+        let url = format!("{}{rel}", self.store_url.as_str());
+        ListingTableUrl::parse(&url)
+            .map_err(|e| Status::internal(format!("invalid table url {url}: {e}")))
+    }
+
+    // Open the backing object store the table paths resolve against.
+    fn object_store(&self) -> tonic::Result<Arc<dyn ObjectStore>> {
+        self.session
+            .runtime_env()
+            .object_store(&self.store_url)
+            .map_err(df_to_status)
+    }
+
+    // Persist the Hive partition layout in a `_partitions` sidecar next to the
+    // table's data, so the read path recovers it after a restart or for a table
+    // written by another process — not just within this process's memory.
+    async fn write_partition_sidecar(
+        &self,
+        dir: &ListingTableUrl,
+        cols: &[(String, DataType)],
+    ) -> tonic::Result<()> {
+        let path = dir.prefix().child("_partitions");
+        let body = cols
+            .iter()
+            .map(|(name, dt)| format!("{name}\t{}", partition_tag(dt)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.object_store()?
+            .put(&path, body.into_bytes().into())
+            .await
+            .map_err(|e| Status::internal(format!("failed to write partition metadata: {e}")))?;
+        Ok(())
+    }
+
+    // Load the partition layout persisted alongside a table; an absent sidecar
+    // means the table is flat.
+    async fn read_partition_cols(
+        &self,
+        dir: &ListingTableUrl,
+    ) -> tonic::Result<Vec<(String, DataType)>> {
+        let path = dir.prefix().child("_partitions");
+        let bytes = match self.object_store()?.get(&path).await {
+            Ok(result) => result
+                .bytes()
+                .await
+                .map_err(|e| Status::internal(format!("failed to read partition metadata: {e}")))?,
+            Err(object_store::Error::NotFound { .. }) => return Ok(vec![]),
+            Err(e) => {
+                return Err(Status::internal(format!("failed to read partition metadata: {e}")))
+            }
+        };
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (name, tag) = line
+                    .split_once('\t')
+                    .ok_or_else(|| Status::internal(format!("malformed partition metadata: {line}")))?;
+                Ok((name.to_string(), partition_type(tag)))
+            })
+            .collect()
+    }
+
+    // Pick where a write lands: inside a transaction it goes to a per-txn
+    // staging directory and is recorded for publish on commit, so an abort
+    // leaves the table untouched; otherwise it writes straight to `table_path`.
+    fn stage_write(
+        &self,
+        transaction_id: &Option<String>,
+        path: &str,
+        table_path: &ListingTableUrl,
+        replace: bool,
+    ) -> tonic::Result<ListingTableUrl> {
+        match transaction_id {
+            Some(txn) => {
+                let staging = self.table_url(&format!("_staging/{txn}/{path}"))?;
+                self.transactions
+                    .lock()
+                    .unwrap()
+                    .entry(txn.clone())
+                    .or_default()
+                    .push(StagedTable {
+                        staging: staging.prefix().clone(),
+                        target: table_path.prefix().clone(),
+                        replace,
+                    });
+                Ok(staging)
+            }
+            None => Ok(table_path.clone()),
+        }
+    }
+
+    // A read observes the published table, not writes staged earlier in the
+    // same transaction. Refuse an operation that would need to see its own
+    // uncommitted writes rather than silently merging against stale data.
+    fn ensure_not_staged(
+        &self,
+        transaction_id: &Option<String>,
+        table_path: &ListingTableUrl,
+    ) -> tonic::Result<()> {
+        if let Some(txn) = transaction_id {
+            let staged = self.transactions.lock().unwrap();
+            let clashes = staged
+                .get(txn)
+                .is_some_and(|tables| tables.iter().any(|t| &t.target == table_path.prefix()));
+            if clashes {
+                return Err(Status::unimplemented(
+                    "reading uncommitted writes within a transaction is not supported; \
+                     commit before a dependent append/upsert/delete on the same table",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Plan a SQL statement against a fresh context, registering every table it
+    // references from its `{catalog}/{schema}/{table}/` directory under the
+    // reference's fully qualified name, declaring any known partition columns
+    // so the Hive-partitioned layout is discovered and pruned.
+    async fn prepare_statement(&self, sql: &str) -> tonic::Result<(SessionContext, LogicalPlan)> {
+        let ctx = SessionContext::new_with_state(self.session.clone());
+
+        let statement = ctx.state().sql_to_statement(sql, "generic").map_err(df_to_status)?;
+        let references = ctx
+            .state()
+            .resolve_table_references(&statement)
+            .map_err(df_to_status)?;
+
+        for reference in references {
+            let catalog = reference.catalog().unwrap_or("datafusion");
+            let schema = reference.schema().unwrap_or("public");
+            let table_url = self.table_url(&format!("{catalog}/{schema}/{}/", reference.table()))?;
+            let partition_cols = self.read_partition_cols(&table_url).await?;
+            let options = ListingOptions::new(Arc::new(ParquetFormat::default()))
+                .with_table_partition_cols(partition_cols);
+            ctx.register_listing_table(reference, table_url.as_str(), options, None, None)
+                .await
+                .map_err(df_to_status)?;
+        }
+
+        let plan = ctx
+            .state()
+            .statement_to_plan(statement)
+            .await
+            .map_err(df_to_status)?;
+
+        Ok((ctx, plan))
+    }
+
+    // Execute `sql` and encode the result batches as a Flight data stream.
+    async fn execute(&self, sql: &str) -> tonic::Result<DoGetStream> {
+        let (ctx, plan) = self.prepare_statement(sql).await?;
+        self.stream_plan(ctx, plan).await
+    }
+
+    // Execute an already-planned statement and encode its batches as a Flight
+    // data stream.
+    async fn stream_plan(
+        &self,
+        ctx: SessionContext,
+        plan: LogicalPlan,
+    ) -> tonic::Result<DoGetStream> {
+        let stream = DataFrame::new(ctx.state(), plan)
+            .execute_stream()
+            .await
+            .map_err(df_to_status)?;
+
+        let flight_stream = FlightDataEncoderBuilder::new()
+            .with_schema(stream.schema())
+            .build(stream.map_err(|e| FlightError::ExternalError(Box::new(e))))
+            .map_err(Status::from);
+
+        Ok(Box::pin(flight_stream))
+    }
+
+    // Plan a prepared statement and bind any parameter values that were put
+    // against its handle, so `$1..$n` placeholders are substituted before exec.
+    async fn prepare_bound(&self, handle: &[u8]) -> tonic::Result<(SessionContext, LogicalPlan)> {
+        let key = String::from_utf8(handle.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid prepared handle: {e}")))?;
+        let sql = self.prepared_sql(handle)?;
+        let (ctx, plan) = self.prepare_statement(&sql).await?;
+        let plan = match self.bindings.lock().unwrap().get(&key).cloned() {
+            Some(values) => plan.with_param_values(values).map_err(df_to_status)?,
+            None => plan,
+        };
+        Ok((ctx, plan))
+    }
+
+    // Build a Parquet sink writing `schema` to the `table_path` directory with
+    // the given insert op. When `partition_cols` are declared the sink emits a
+    // Hive-partitioned (`col=value/...`) layout; the columns are encoded in the
+    // path (not the files) and recovered by the read path from the partition
+    // columns it registers.
+    fn build_sink(
+        &self,
+        table_path: ListingTableUrl,
+        schema: arrow::datatypes::SchemaRef,
+        insert_op: InsertOp,
+        partition_cols: Vec<(String, DataType)>,
+    ) -> ParquetSink {
+        let file_sink_config = FileSinkConfig {
+            object_store_url: self.store_url.clone(),
+            file_groups: vec![],
+            table_paths: vec![table_path],
+            output_schema: schema,
+            table_partition_cols: partition_cols,
+            insert_op,
+            keep_partition_by_columns: false,
+            file_extension: String::from("parquet"),
+        };
+        ParquetSink::new(file_sink_config, Default::default())
+    }
+
+    // Drive a record-batch stream into a sink, isolating the write on the
+    // dedicated IO runtime when that feature is enabled.
+    async fn write_all(&self, sink: ParquetSink, stream: SendableRecordBatchStream) -> u64 {
+        let task_ctx = SessionContext::new_with_state(self.session.clone()).task_ctx();
+        println!("writing data to object store");
+        #[cfg(feature = "dedicated-executor")]
+        let rows_written = self
+            .exec
+            .spawn(async move { sink.write_all(stream, &task_ctx).await.unwrap() })
+            .await
+            .unwrap();
+        #[cfg(not(feature = "dedicated-executor"))]
+        let rows_written = sink.write_all(stream, &task_ctx).await.unwrap();
+        println!("wrote {rows_written} rows");
+        rows_written
+    }
+
+    // Register the incoming batches as `__incoming` and, if the table already
+    // exists, the published data as `__existing`. Returns the output column
+    // names (the existing table's columns, or the incoming columns on first
+    // write).
+    async fn register_merge_sources(
+        &self,
+        ctx: &SessionContext,
+        table_path: &ListingTableUrl,
+        schema: arrow::datatypes::SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> tonic::Result<Vec<String>> {
+        let incoming = MemTable::try_new(schema.clone(), vec![batches]).map_err(df_to_status)?;
+        ctx.register_table("__incoming", Arc::new(incoming))
+            .map_err(df_to_status)?;
+
+        // Read published data through the same partition-aware listing the query
+        // path uses, so a merge against a Hive-partitioned table keeps the
+        // partition columns instead of dropping them from the rewrite.
+        let store = self.object_store()?;
+        let has_data = list_prefix(&store, table_path.prefix())
+            .await?
+            .iter()
+            .any(|path| path.as_ref().ends_with(".parquet"));
+
+        let columns = if has_data {
+            let partition_cols = self.read_partition_cols(table_path).await?;
+            let options = ListingOptions::new(Arc::new(ParquetFormat::default()))
+                .with_table_partition_cols(partition_cols);
+            ctx.register_listing_table("__existing", table_path.as_str(), options, None, None)
+                .await
+                .map_err(df_to_status)?;
+            ctx.table("__existing")
+                .await
+                .map_err(df_to_status)?
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .collect()
+        } else {
+            schema.fields().iter().map(|f| f.name().clone()).collect()
+        };
+
+        Ok(columns)
+    }
+
+    // Rewrite `table_path` from the merged result of `sql`, overwriting in place.
+    async fn rewrite(
+        &self,
+        ctx: &SessionContext,
+        table_path: ListingTableUrl,
+        sql: &str,
+    ) -> tonic::Result<u64> {
+        let stream = ctx
+            .sql(sql)
+            .await
+            .map_err(df_to_status)?
+            .execute_stream()
+            .await
+            .map_err(df_to_status)?;
+        let sink = self.build_sink(table_path, stream.schema(), InsertOp::Overwrite, vec![]);
+        Ok(self.write_all(sink, stream).await)
+    }
+
+    // Merge by `keys`: existing rows whose keys are superseded are replaced with
+    // the incoming row, carrying over any columns absent from the incoming
+    // schema.
+    async fn upsert(
+        &self,
+        ctx: &SessionContext,
+        table_path: &ListingTableUrl,
+        write_path: ListingTableUrl,
+        schema: arrow::datatypes::SchemaRef,
+        stream: SendableRecordBatchStream,
+        keys: &[String],
+    ) -> tonic::Result<u64> {
+        let batches: Vec<RecordBatch> = stream.try_collect().await.map_err(df_to_status)?;
+        let incoming: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+        let columns = self
+            .register_merge_sources(ctx, table_path, schema.clone(), batches)
+            .await?;
+
+        let on = keys
+            .iter()
+            .map(|k| format!("e.\"{k}\" = i.\"{k}\""))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+
+        // First write of this table: nothing to merge against.
+        if !ctx.table_exist("__existing").unwrap_or(false) {
+            let list = columns
+                .iter()
+                .map(|c| format!("i.\"{c}\""))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return self
+                .rewrite(ctx, write_path, &format!("SELECT {list} FROM __incoming i"))
+                .await;
+        }
+
+        let merged = columns
+            .iter()
+            .map(|c| {
+                if incoming.contains(c) {
+                    format!("i.\"{c}\" AS \"{c}\"")
+                } else {
+                    format!("e.\"{c}\" AS \"{c}\"")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let untouched = columns
+            .iter()
+            .map(|c| format!("e.\"{c}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let sql = format!(
+            "SELECT {untouched} FROM __existing e \
+             WHERE NOT EXISTS (SELECT 1 FROM __incoming i WHERE {on}) \
+             UNION ALL \
+             SELECT {merged} FROM __incoming i LEFT JOIN __existing e ON {on}"
+        );
+        self.rewrite(ctx, write_path, &sql).await
+    }
+
+    // Anti-join the existing table against the incoming key batch and rewrite
+    // the survivors.
+    async fn delete(
+        &self,
+        ctx: &SessionContext,
+        table_path: &ListingTableUrl,
+        write_path: ListingTableUrl,
+        schema: arrow::datatypes::SchemaRef,
+        stream: SendableRecordBatchStream,
+        keys: &[String],
+    ) -> tonic::Result<u64> {
+        let batches: Vec<RecordBatch> = stream.try_collect().await.map_err(df_to_status)?;
+        let columns = self
+            .register_merge_sources(ctx, table_path, schema, batches)
+            .await?;
+
+        // Nothing published yet — deleting from an empty table is a no-op.
+        if !ctx.table_exist("__existing").unwrap_or(false) {
+            return Ok(0);
+        }
+
+        let on = keys
+            .iter()
+            .map(|k| format!("e.\"{k}\" = i.\"{k}\""))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let list = columns
+            .iter()
+            .map(|c| format!("e.\"{c}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let sql = format!(
+            "SELECT {list} FROM __existing e \
+             WHERE NOT EXISTS (SELECT 1 FROM __incoming i WHERE {on})"
+        );
+        self.rewrite(ctx, write_path, &sql).await
+    }
+
+    // Error if an append targets an existing table whose footer schema differs
+    // from the incoming one. Partition columns are encoded in the path, not the
+    // data files, so they are excluded before comparing against the footer.
+    async fn check_append_schema(
+        &self,
+        ctx: &SessionContext,
+        target: &ListingTableUrl,
+        schema: &arrow::datatypes::SchemaRef,
+        partition_cols: &[(String, DataType)],
+    ) -> tonic::Result<()> {
+        if let Ok(df) = ctx
+            .read_parquet(target.as_str(), ParquetReadOptions::default())
+            .await
+        {
+            let existing = df.schema().as_arrow();
+            let part_names: HashSet<&str> =
+                partition_cols.iter().map(|(name, _)| name.as_str()).collect();
+            let data_fields: Vec<_> = schema
+                .fields()
+                .iter()
+                .filter(|f| !part_names.contains(f.name().as_str()))
+                .cloned()
+                .collect();
+            if existing.fields()[..] != data_fields[..] {
+                return Err(Status::failed_precondition(format!(
+                    "append schema {:?} does not match existing table footer {:?}",
+                    data_fields,
+                    existing.fields()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Validate the credential presented on a handshake against the configured
+    // bearer token (FLIGHT_SQL_TOKEN) or basic credential (FLIGHT_SQL_BASIC, the
+    // base64 `user:password` value the client sends).
+    fn check_credentials(&self, metadata: &MetadataMap) -> tonic::Result<()> {
+        let header = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("missing authorization header"))?;
+
+        let ok = if let Some(token) = header.strip_prefix("Bearer ") {
+            env::var("FLIGHT_SQL_TOKEN").is_ok_and(|expected| expected == token)
+        } else if let Some(basic) = header.strip_prefix("Basic ") {
+            env::var("FLIGHT_SQL_BASIC").is_ok_and(|expected| expected == basic)
+        } else {
+            false
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("invalid credentials"))
+        }
+    }
+
+    // Reject a call that doesn't carry a bearer session token from a prior
+    // successful handshake.
+    fn authenticate(&self, metadata: &MetadataMap) -> tonic::Result<()> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing session token"))?;
+
+        if self.tokens.lock().unwrap().contains(token) {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("invalid or expired session token"))
+        }
+    }
+
+    fn issue_token(&self) -> String {
+        // An unguessable random token, so a client can't enumerate the shared
+        // id counter to forge a session bearer.
+        let token = Uuid::new_v4().to_string();
+        self.tokens.lock().unwrap().insert(token.clone());
+        token
+    }
+
+    fn prepared_sql(&self, handle: &[u8]) -> tonic::Result<String> {
+        let handle = String::from_utf8(handle.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid prepared handle: {e}")))?;
+        self.prepared
+            .lock()
+            .unwrap()
+            .get(&handle)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("unknown prepared statement {handle}")))
+    }
+}
+
+// Resolve the Hive partition columns declared on the ingest ticket
+// (`partition_by` = comma-separated column names) against the incoming schema.
+fn partition_cols(
+    ticket: &CommandStatementIngest,
+    schema: &arrow::datatypes::SchemaRef,
+) -> tonic::Result<Vec<(String, DataType)>> {
+    let raw = ticket
+        .options
+        .get("partition_by")
+        .map(String::as_str)
+        .unwrap_or("");
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|name| {
+            let field = schema
+                .field_with_name(name)
+                .map_err(|_| Status::invalid_argument(format!("unknown partition column `{name}`")))?;
+            Ok((name.to_string(), field.data_type().clone()))
+        })
+        .collect()
+}
+
+// Compact, stable tags for the partition-column types we persist in the
+// `_partitions` sidecar. Anything else round-trips as Utf8, which is how Hive
+// directory values read back.
+fn partition_tag(dt: &DataType) -> &'static str {
+    match dt {
+        DataType::Boolean => "bool",
+        DataType::Int8 => "i8",
+        DataType::Int16 => "i16",
+        DataType::Int32 => "i32",
+        DataType::Int64 => "i64",
+        DataType::UInt8 => "u8",
+        DataType::UInt16 => "u16",
+        DataType::UInt32 => "u32",
+        DataType::UInt64 => "u64",
+        DataType::Float32 => "f32",
+        DataType::Float64 => "f64",
+        DataType::Date32 => "date32",
+        DataType::Date64 => "date64",
+        _ => "utf8",
+    }
+}
+
+fn partition_type(tag: &str) -> DataType {
+    match tag {
+        "bool" => DataType::Boolean,
+        "i8" => DataType::Int8,
+        "i16" => DataType::Int16,
+        "i32" => DataType::Int32,
+        "i64" => DataType::Int64,
+        "u8" => DataType::UInt8,
+        "u16" => DataType::UInt16,
+        "u32" => DataType::UInt32,
+        "u64" => DataType::UInt64,
+        "f32" => DataType::Float32,
+        "f64" => DataType::Float64,
+        "date32" => DataType::Date32,
+        "date64" => DataType::Date64,
+        _ => DataType::Utf8,
+    }
+}
+
+// Turn the parameter types DataFusion inferred for a prepared statement into a
+// schema, ordering the positional `$1..$n` placeholders and defaulting an
+// untyped placeholder to Utf8.
+fn parameter_schema(types: &HashMap<String, Option<DataType>>) -> Schema {
+    let mut params: Vec<(&String, &Option<DataType>)> = types.iter().collect();
+    params.sort_by_key(|(name, _)| name.trim_start_matches('$').parse::<usize>().unwrap_or(usize::MAX));
+    let fields: Vec<Field> = params
+        .into_iter()
+        .map(|(name, dt)| Field::new(name, dt.clone().unwrap_or(DataType::Utf8), true))
+        .collect();
+    Schema::new(fields)
+}
+
+// Flatten the bound parameter batch into positional values for `$1..$n`.
+fn param_values(batches: &[RecordBatch]) -> tonic::Result<Vec<ScalarValue>> {
+    let mut values = Vec::new();
+    for batch in batches {
+        for row in 0..batch.num_rows() {
+            for column in batch.columns() {
+                values.push(ScalarValue::try_from_array(column, row).map_err(df_to_status)?);
+            }
+        }
+    }
+    Ok(values)
+}
+
+// Fill in DataFusion's default catalog/schema when the client omits them, so
+// the ingest write path and the query read path resolve to the same object.
+fn qualify<'a>(catalog: &'a str, schema: &'a str) -> (&'a str, &'a str) {
+    (
+        if catalog.is_empty() { "datafusion" } else { catalog },
+        if schema.is_empty() { "public" } else { schema },
+    )
+}
+
+// Map the ingest's table-definition options onto an insert op: an explicit
+// "append on exists" accumulates into the table's file group, everything else
+// overwrites.
+fn ingest_insert_op(ticket: &CommandStatementIngest) -> InsertOp {
+    match ticket.table_definition_options.as_ref().map(|o| o.if_exists()) {
+        Some(TableExistsOption::Append) => InsertOp::Append,
+        _ => InsertOp::Overwrite,
+    }
+}
+
 #[tonic::async_trait]
 impl FlightSqlService for FlightSql {
     type FlightService = Self;
@@ -46,12 +770,129 @@ impl FlightSqlService for FlightSql {
         unimplemented!()
     }
 
+    async fn do_handshake(
+        &self,
+        request: Request<Streaming<HandshakeRequest>>,
+    ) -> tonic::Result<
+        Response<Pin<Box<dyn Stream<Item = Result<HandshakeResponse, Status>> + Send>>>,
+    > {
+        self.check_credentials(request.metadata())?;
+
+        // Mint a session token and hand it back both in the handshake payload
+        // and as the bearer the client echoes on subsequent calls.
+        let token = self.issue_token();
+        let result = HandshakeResponse {
+            protocol_version: 0,
+            payload: token.clone().into_bytes().into(),
+        };
+        let output = futures::stream::once(future::ready(Ok(result)));
+
+        let mut response = Response::new(Box::pin(output) as _);
+        response.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {token}")
+                .parse()
+                .expect("token is valid ascii"),
+        );
+        Ok(response)
+    }
+
+    async fn get_flight_info_statement(
+        &self,
+        query: CommandStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> tonic::Result<Response<FlightInfo>> {
+        self.authenticate(request.metadata())?;
+
+        // Plan the query so we can advertise its output schema, then hand the
+        // client back a ticket carrying the query for the matching do_get.
+        let (_, plan) = self.prepare_statement(&query.query).await?;
+        let schema = plan.schema().as_arrow().clone();
+
+        let ticket = Ticket {
+            ticket: query.as_any().encode_to_vec().into(),
+        };
+        let endpoint = FlightEndpoint::new().with_ticket(ticket);
+        let info = FlightInfo::new()
+            .try_with_schema(&schema)
+            .map_err(|e| Status::internal(format!("failed to encode schema: {e}")))?
+            .with_endpoint(endpoint)
+            .with_descriptor(request.into_inner());
+
+        Ok(Response::new(info))
+    }
+
+    async fn do_get_statement(
+        &self,
+        ticket: TicketStatementQuery,
+        request: Request<Ticket>,
+    ) -> tonic::Result<Response<DoGetStream>> {
+        self.authenticate(request.metadata())?;
+        let sql = String::from_utf8(ticket.statement_handle.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid statement handle: {e}")))?;
+        Ok(Response::new(self.execute(&sql).await?))
+    }
+
+    async fn do_get_fallback(
+        &self,
+        request: Request<Ticket>,
+        message: Any,
+    ) -> tonic::Result<Response<DoGetStream>> {
+        self.authenticate(request.metadata())?;
+        if !message.is::<CommandStatementQuery>() {
+            return Err(Status::unimplemented(format!(
+                "do_get_fallback: unsupported ticket {}",
+                message.type_url
+            )));
+        }
+        let query: CommandStatementQuery = message
+            .unpack()
+            .map_err(|e| Status::invalid_argument(format!("failed to decode ticket: {e}")))?
+            .expect("ticket verified as CommandStatementQuery above");
+
+        println!("executing query: {}", query.query);
+        Ok(Response::new(self.execute(&query.query).await?))
+    }
+
+    async fn get_flight_info_prepared_statement(
+        &self,
+        cmd: CommandPreparedStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> tonic::Result<Response<FlightInfo>> {
+        self.authenticate(request.metadata())?;
+        let (_, plan) = self.prepare_bound(&cmd.prepared_statement_handle).await?;
+        let schema = plan.schema().as_arrow().clone();
+
+        let ticket = Ticket {
+            ticket: cmd.as_any().encode_to_vec().into(),
+        };
+        let endpoint = FlightEndpoint::new().with_ticket(ticket);
+        let info = FlightInfo::new()
+            .try_with_schema(&schema)
+            .map_err(|e| Status::internal(format!("failed to encode schema: {e}")))?
+            .with_endpoint(endpoint)
+            .with_descriptor(request.into_inner());
+
+        Ok(Response::new(info))
+    }
+
+    async fn do_get_prepared_statement(
+        &self,
+        cmd: CommandPreparedStatementQuery,
+        request: Request<Ticket>,
+    ) -> tonic::Result<Response<DoGetStream>> {
+        self.authenticate(request.metadata())?;
+        let (ctx, plan) = self.prepare_bound(&cmd.prepared_statement_handle).await?;
+        Ok(Response::new(self.stream_plan(ctx, plan).await?))
+    }
+
     async fn do_put_statement_ingest(
         &self,
         ticket: CommandStatementIngest,
         request: Request<PeekableFlightDataStream>,
     ) -> tonic::Result<i64> {
         println!("Got a request from {:?}", request.remote_addr());
+        self.authenticate(request.metadata())?;
 
         let ctx = SessionContext::new_with_state(self.session.clone());
         let mut flight_data_stream = request.into_inner();
@@ -77,87 +918,327 @@ impl FlightSqlService for FlightSql {
             future::ready(Some(fd))
         });
 
-        let path = format!(
-            "{}/{}/{}.parquet",
-            ticket.catalog(),
-            ticket.schema(),
-            ticket.table
-        );
-        let table_path = ListingTableUrl::parse(format!("/{}", path))
-            .map_err(|e| Status::internal(format!("invalid table url {path}: {e}")))?;
+        let command = DoPutCommand::from_options(&ticket.options)?;
 
-        // Configure sink
-        let file_sink_config = FileSinkConfig {
-            object_store_url: ObjectStoreUrl::local_filesystem(),
-            file_groups: vec![],
-            table_paths: vec![table_path],
-            output_schema: schema.clone(),
-            table_partition_cols: vec![],
-            insert_op: InsertOp::Overwrite,
-            keep_partition_by_columns: false,
-            file_extension: String::from("parquet"),
-        };
-        let table_options = Default::default();
-        let data_sink = ParquetSink::new(file_sink_config, table_options);
+        let (catalog, schema_name) = qualify(ticket.catalog(), ticket.schema());
+        // Each table is a directory of Parquet files so the Hive-partitioned
+        // layout has somewhere to live.
+        let key = format!("{catalog}/{schema_name}/{}", ticket.table);
+        let path = format!("{key}/");
+        let table_path = self.table_url(&path)?;
 
         let record_batch_stream =
             FlightRecordBatchStream::new_from_flight_data(flight_data_stream.map_err(|e| e.into()));
 
         // Wrap Arrow Flight stream of record batches in DataFusion adapter
         let stream: SendableRecordBatchStream = Box::pin(RecordBatchStreamAdapter::new(
-            schema,
+            schema.clone(),
             record_batch_stream.map_err(|e| DataFusionError::External(Box::new(e))),
         ));
 
-        // Execute write on dedicated runtime
-        println!("writing data to object store");
-        #[cfg(feature = "dedicated-executor")]
-        let rows_written = self
-            .exec
-            .spawn(async move { data_sink.write_all(stream, &ctx.task_ctx()).await.unwrap() })
-            .await
-            .unwrap();
-        #[cfg(not(feature = "dedicated-executor"))]
-        let rows_written = data_sink.write_all(stream, &ctx.task_ctx()).await.unwrap();
+        let transaction_id = match &ticket.transaction_id {
+            Some(id) if !id.is_empty() => Some(String::from_utf8_lossy(id.as_ref()).into_owned()),
+            _ => None,
+        };
 
-        println!("wrote {rows_written} rows");
+        let rows_written = match command {
+            DoPutCommand::Overwrite => {
+                let insert_op = ingest_insert_op(&ticket);
+                let partition_cols = partition_cols(&ticket, &schema)?;
+                if insert_op == InsertOp::Append {
+                    self.ensure_not_staged(&transaction_id, &table_path)?;
+                    self.check_append_schema(&ctx, &table_path, &schema, &partition_cols)
+                        .await?;
+                }
+                // Append keeps the existing file group; overwrite replaces it.
+                let replace = insert_op != InsertOp::Append;
+                let write_path =
+                    self.stage_write(&transaction_id, &path, &table_path, replace)?;
+                // Overwrite (re)defines the partition layout and records it next
+                // to the data; an append keeps the existing sidecar.
+                if replace {
+                    self.write_partition_sidecar(&write_path, &partition_cols).await?;
+                }
 
-        //self.exec.join().await;
+                let sink = self.build_sink(write_path, schema, insert_op, partition_cols);
+                self.write_all(sink, stream).await
+            }
+            DoPutCommand::Upsert { keys } => {
+                self.ensure_not_staged(&transaction_id, &table_path)?;
+                let write_path =
+                    self.stage_write(&transaction_id, &path, &table_path, true)?;
+                // A merge flattens the table, so the published copy has no
+                // partition columns. The sidecar is rewritten only after the
+                // merge has read the existing layout.
+                let rows = self
+                    .upsert(&ctx, &table_path, write_path.clone(), schema, stream, &keys)
+                    .await?;
+                self.write_partition_sidecar(&write_path, &[]).await?;
+                rows
+            }
+            DoPutCommand::Delete { keys } => {
+                self.ensure_not_staged(&transaction_id, &table_path)?;
+                let write_path =
+                    self.stage_write(&transaction_id, &path, &table_path, true)?;
+                let rows = self
+                    .delete(&ctx, &table_path, write_path.clone(), schema, stream, &keys)
+                    .await?;
+                self.write_partition_sidecar(&write_path, &[]).await?;
+                rows
+            }
+        };
 
         Ok(rows_written as i64)
     }
+
+    async fn do_action_begin_transaction(
+        &self,
+        _query: ActionBeginTransactionRequest,
+        request: Request<Action>,
+    ) -> tonic::Result<ActionBeginTransactionResult> {
+        self.authenticate(request.metadata())?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let transaction_id = format!("txn-{id}");
+        self.transactions
+            .lock()
+            .unwrap()
+            .insert(transaction_id.clone(), Vec::new());
+        Ok(ActionBeginTransactionResult {
+            transaction_id: transaction_id.into_bytes().into(),
+        })
+    }
+
+    async fn do_action_end_transaction(
+        &self,
+        query: ActionEndTransactionRequest,
+        request: Request<Action>,
+    ) -> tonic::Result<()> {
+        self.authenticate(request.metadata())?;
+        let txn = String::from_utf8(query.transaction_id.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid transaction id: {e}")))?;
+        let action = query.action();
+        let staged = self
+            .transactions
+            .lock()
+            .unwrap()
+            .remove(&txn)
+            .ok_or_else(|| Status::not_found(format!("unknown transaction {txn}")))?;
+
+        let store = self
+            .session
+            .runtime_env()
+            .object_store(&self.store_url)
+            .map_err(df_to_status)?;
+
+        match action {
+            EndTransaction::Commit => {
+                for table in staged {
+                    // A replace (overwrite/merge) clears the published file
+                    // group first; an append adds to it.
+                    if table.replace {
+                        clear_prefix(&store, &table.target).await?;
+                    }
+                    // Publish every staged file, re-rooting its path (including
+                    // any Hive partition directories) under the target.
+                    let staged_files = list_prefix(&store, &table.staging).await?;
+                    for location in &staged_files {
+                        let rel = location
+                            .as_ref()
+                            .strip_prefix(table.staging.as_ref())
+                            .unwrap_or(location.as_ref())
+                            .trim_start_matches('/');
+                        let dest = object_store::path::Path::from(format!("{}/{rel}", table.target));
+                        store
+                            .copy(location, &dest)
+                            .await
+                            .map_err(|e| Status::internal(format!("commit copy failed: {e}")))?;
+                    }
+                    for location in &staged_files {
+                        let _ = store.delete(location).await;
+                    }
+                }
+            }
+            EndTransaction::Rollback => {
+                for table in staged {
+                    for location in list_prefix(&store, &table.staging).await? {
+                        let _ = store.delete(&location).await;
+                    }
+                }
+            }
+            EndTransaction::Unspecified => {
+                return Err(Status::invalid_argument("unspecified end-transaction action"));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn do_action_create_prepared_statement(
+        &self,
+        query: ActionCreatePreparedStatementRequest,
+        request: Request<Action>,
+    ) -> tonic::Result<ActionCreatePreparedStatementResult> {
+        self.authenticate(request.metadata())?;
+        // Plan the statement now so we can report its output schema and fail
+        // fast on an invalid query; keep the SQL under a handle for later bind
+        // and execution.
+        let (_, plan) = self.prepare_statement(&query.query).await?;
+        let schema = plan.schema().as_arrow().clone();
+        // Report the inferred parameter types so the client can bind values of
+        // the right shape before execution.
+        let param_types = plan.get_parameter_types().map_err(df_to_status)?;
+        let params = parameter_schema(&param_types);
+
+        let handle = format!("stmt-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.prepared
+            .lock()
+            .unwrap()
+            .insert(handle.clone(), query.query);
+
+        let options = IpcWriteOptions::default();
+        let IpcMessage(dataset_schema) = SchemaAsIpc::new(&schema, &options)
+            .try_into()
+            .map_err(|e| Status::internal(format!("failed to encode dataset schema: {e}")))?;
+        let IpcMessage(parameter_schema) = SchemaAsIpc::new(&params, &options)
+            .try_into()
+            .map_err(|e| Status::internal(format!("failed to encode parameter schema: {e}")))?;
+
+        Ok(ActionCreatePreparedStatementResult {
+            prepared_statement_handle: handle.into_bytes().into(),
+            dataset_schema,
+            parameter_schema,
+        })
+    }
+
+    // Bind the parameter values carried in the put stream to a prepared
+    // statement handle; they are applied when the statement is next executed.
+    async fn do_put_prepared_statement_query(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<PeekableFlightDataStream>,
+    ) -> tonic::Result<Response<<Self as FlightService>::DoPutStream>> {
+        self.authenticate(request.metadata())?;
+        let key = String::from_utf8(query.prepared_statement_handle.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid prepared handle: {e}")))?;
+        if !self.prepared.lock().unwrap().contains_key(&key) {
+            return Err(Status::not_found(format!("unknown prepared statement {key}")));
+        }
+
+        let batches: Vec<RecordBatch> = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(|e| e.into()),
+        )
+        .try_collect()
+        .await
+        .map_err(df_to_status)?;
+        self.bindings
+            .lock()
+            .unwrap()
+            .insert(key.clone(), param_values(&batches)?);
+
+        let result = DoPutPreparedStatementResult {
+            prepared_statement_handle: Some(key.into_bytes().into()),
+        };
+        let output = futures::stream::once(future::ready(Ok(PutResult {
+            app_metadata: result.encode_to_vec().into(),
+        })));
+        Ok(Response::new(Box::pin(output)))
+    }
+
+    async fn do_action_close_prepared_statement(
+        &self,
+        query: ActionClosePreparedStatementRequest,
+        request: Request<Action>,
+    ) -> tonic::Result<()> {
+        self.authenticate(request.metadata())?;
+        let handle = String::from_utf8(query.prepared_statement_handle.to_vec())
+            .map_err(|e| Status::invalid_argument(format!("invalid prepared handle: {e}")))?;
+        self.prepared.lock().unwrap().remove(&handle);
+        self.bindings.lock().unwrap().remove(&handle);
+        Ok(())
+    }
+}
+
+// Build the configured backing object store from the environment, paired with
+// the `ObjectStoreUrl` scheme it is registered (and table paths resolved)
+// under. LocalStack is handled separately in `main` because it owns a
+// container guard.
+fn build_object_store(backend: &str) -> (Arc<dyn ObjectStore>, ObjectStoreUrl) {
+    let bucket = || env::var("OBJECT_STORE_BUCKET").expect("OBJECT_STORE_BUCKET");
+    let container = || env::var("OBJECT_STORE_CONTAINER").expect("OBJECT_STORE_CONTAINER");
+    match backend {
+        "s3" => {
+            let bucket = bucket();
+            let store = Arc::new(AmazonS3Builder::from_env().with_bucket_name(&bucket).build().unwrap());
+            (store, object_store_url(&format!("s3://{bucket}")))
+        }
+        "gcs" => {
+            let bucket = bucket();
+            let store =
+                Arc::new(GoogleCloudStorageBuilder::from_env().with_bucket_name(&bucket).build().unwrap());
+            (store, object_store_url(&format!("gs://{bucket}")))
+        }
+        "azure" => {
+            let container = container();
+            let store =
+                Arc::new(MicrosoftAzureBuilder::from_env().with_container_name(&container).build().unwrap());
+            (store, object_store_url(&format!("az://{container}")))
+        }
+        "local" => (Arc::new(LocalFileSystem::new()), ObjectStoreUrl::local_filesystem()),
+        other => panic!("unsupported OBJECT_STORE_BACKEND `{other}`"),
+    }
+}
+
+fn object_store_url(url: &str) -> ObjectStoreUrl {
+    ObjectStoreUrl::parse(url).expect("valid object store url")
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().unwrap();
 
-    println!("Starting localstack object store");
-    let localstack = localstack::localstack_container().await;
-    let localstack_host = localstack.get_host().await.unwrap();
-    let localstack_port = localstack.get_host_port_ipv4(4566).await.unwrap();
+    let backend = env::var("OBJECT_STORE_BACKEND").unwrap_or_else(|_| "localstack".to_string());
+    println!("Using object store backend `{backend}`");
 
     #[cfg(feature = "dedicated-executor")]
     let exec = DedicatedExecutorBuilder::new().build();
 
-    let store: Arc<dyn ObjectStore> = Arc::new(
-        AmazonS3Builder::new()
-            .with_endpoint(format!("http://{}:{}", localstack_host, localstack_port))
-            .with_allow_http(true)
-            .with_bucket_name("warehouse")
-            .with_access_key_id("user")
-            .with_secret_access_key("password")
-            .build()
-            .unwrap(),
-    );
+    // Build the configured backend. LocalStack owns a container guard that must
+    // outlive the server, so it stays inline; every other backend is built from
+    // the environment by `build_object_store`.
+    let (store, store_url, _localstack) = match backend.as_str() {
+        "localstack" => {
+            println!("Starting localstack object store");
+            let localstack = localstack::localstack_container().await;
+            let host = localstack.get_host().await.unwrap();
+            let port = localstack.get_host_port_ipv4(4566).await.unwrap();
+            let store: Arc<dyn ObjectStore> = Arc::new(
+                AmazonS3Builder::new()
+                    .with_endpoint(format!("http://{host}:{port}"))
+                    .with_allow_http(true)
+                    .with_bucket_name("warehouse")
+                    .with_access_key_id("user")
+                    .with_secret_access_key("password")
+                    .build()
+                    .unwrap(),
+            );
+            (store, object_store_url("s3://warehouse"), Some(localstack))
+        }
+        other => {
+            let (store, url) = build_object_store(other);
+            (store, url, None)
+        }
+    };
+
     #[cfg(feature = "dedicated-executor")]
     let store = exec.wrap_object_store_for_io(store);
 
     let config =
         SessionConfig::new().set_str("datafusion.execution.parquet.compression", "zstd(19)");
 
+    // Register the store under the scheme chosen for the backend; table paths
+    // are resolved as `{store_url}/{catalog}/{schema}/{table}` against it.
     let object_store_registery = Arc::new(DefaultObjectStoreRegistry::default());
-    object_store_registery.register_store(ObjectStoreUrl::local_filesystem().as_ref(), store);
+    object_store_registery.register_store(store_url.as_ref(), store);
 
     let runtime_env = RuntimeEnvBuilder::new()
         .with_object_store_registry(object_store_registery)
@@ -173,6 +1254,12 @@ async fn main() {
     let addr = "[::1]:50051".parse().unwrap();
     let flight_sql_svc = FlightServiceServer::new(FlightSql {
         session,
+        store_url,
+        transactions: Arc::new(Mutex::new(HashMap::new())),
+        tokens: Arc::new(Mutex::new(HashSet::new())),
+        prepared: Arc::new(Mutex::new(HashMap::new())),
+        bindings: Arc::new(Mutex::new(HashMap::new())),
+        next_id: Arc::new(AtomicU64::new(0)),
         #[cfg(feature = "dedicated-executor")]
         exec,
     })